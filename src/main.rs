@@ -15,17 +15,123 @@ use log;
 use env_logger;
 use tempfile::Builder;
 
+/// Hand-vendored bindings for `ISetupConfiguration`/`ISetupInstance`/`IEnumSetupInstances`
+/// (the VS Setup Configuration COM API). These interfaces aren't part of the `windows`
+/// crate's Win32 metadata, so they're declared here from their public, documented IIDs
+/// and vtable layouts rather than generated.
+#[cfg(windows)]
+mod setup_configuration {
+    use std::ffi::c_void;
+    use windows::core::{Interface, Result, BSTR, GUID, HRESULT, IUnknown, IUnknown_Vtbl};
+
+    #[repr(C)]
+    #[allow(non_snake_case)]
+    pub struct ISetupConfiguration_Vtbl {
+        pub base: IUnknown_Vtbl,
+        pub EnumInstances: unsafe extern "system" fn(this: *mut c_void, ppenuminstances: *mut *mut c_void) -> HRESULT,
+        pub GetInstanceForCurrentProcess: unsafe extern "system" fn(this: *mut c_void, ppinstance: *mut *mut c_void) -> HRESULT,
+        pub GetInstanceForPath: unsafe extern "system" fn(this: *mut c_void, wzpath: *const u16, ppinstance: *mut *mut c_void) -> HRESULT,
+    }
+
+    #[repr(transparent)]
+    #[derive(Clone, PartialEq, Eq, Debug)]
+    pub struct ISetupConfiguration(IUnknown);
+
+    unsafe impl Interface for ISetupConfiguration {
+        type Vtable = ISetupConfiguration_Vtbl;
+        const IID: GUID = GUID::from_u128(0x42843719_DB4C_46C2_8E7C_64F1816EFD5B);
+    }
+
+    impl ISetupConfiguration {
+        pub unsafe fn enum_instances(&self) -> Result<IEnumSetupInstances> {
+            let mut result = std::ptr::null_mut();
+            (Interface::vtable(self).EnumInstances)(Interface::as_raw(self), &mut result).ok()?;
+            Ok(IEnumSetupInstances::from_raw(result))
+        }
+    }
+
+    #[repr(C)]
+    #[allow(non_snake_case)]
+    pub struct IEnumSetupInstances_Vtbl {
+        pub base: IUnknown_Vtbl,
+        pub Next: unsafe extern "system" fn(this: *mut c_void, celt: u32, rgelt: *mut *mut c_void, pceltfetched: *mut u32) -> HRESULT,
+        pub Skip: unsafe extern "system" fn(this: *mut c_void, celt: u32) -> HRESULT,
+        pub Reset: unsafe extern "system" fn(this: *mut c_void) -> HRESULT,
+        pub Clone: unsafe extern "system" fn(this: *mut c_void, ppenum: *mut *mut c_void) -> HRESULT,
+    }
+
+    #[repr(transparent)]
+    #[derive(Clone, PartialEq, Eq, Debug)]
+    pub struct IEnumSetupInstances(IUnknown);
+
+    unsafe impl Interface for IEnumSetupInstances {
+        type Vtable = IEnumSetupInstances_Vtbl;
+        const IID: GUID = GUID::from_u128(0x6380BCFF_41D3_4B2E_8B2E_BF8A6810C848);
+    }
+
+    impl IEnumSetupInstances {
+        /// Fetch the next instance, if any. Mirrors calling `Next(1, ..)` in a loop.
+        pub unsafe fn next_one(&self) -> Result<Option<ISetupInstance>> {
+            let mut fetched = 0u32;
+            let mut result = std::ptr::null_mut();
+            (Interface::vtable(self).Next)(Interface::as_raw(self), 1, &mut result, &mut fetched).ok()?;
+            Ok(if fetched == 0 { None } else { Some(ISetupInstance::from_raw(result)) })
+        }
+    }
+
+    #[repr(C)]
+    #[allow(non_snake_case)]
+    pub struct ISetupInstance_Vtbl {
+        pub base: IUnknown_Vtbl,
+        pub GetInstanceId: unsafe extern "system" fn(this: *mut c_void, pbstrinstanceid: *mut BSTR) -> HRESULT,
+        pub GetInstallDate: unsafe extern "system" fn(this: *mut c_void, pinstalldate: *mut u64) -> HRESULT,
+        pub GetInstallationName: unsafe extern "system" fn(this: *mut c_void, pbstrinstallationname: *mut BSTR) -> HRESULT,
+        pub GetInstallationPath: unsafe extern "system" fn(this: *mut c_void, pbstrinstallationpath: *mut BSTR) -> HRESULT,
+        pub GetInstallationVersion: unsafe extern "system" fn(this: *mut c_void, pbstrinstallationversion: *mut BSTR) -> HRESULT,
+        pub GetDisplayName: unsafe extern "system" fn(this: *mut c_void, lcid: u32, pbstrdisplayname: *mut BSTR) -> HRESULT,
+        pub GetDescription: unsafe extern "system" fn(this: *mut c_void, lcid: u32, pbstrdescription: *mut BSTR) -> HRESULT,
+        pub ResolvePath: unsafe extern "system" fn(this: *mut c_void, pwszrelativepath: *const u16, pbstrabsolutepath: *mut BSTR) -> HRESULT,
+    }
+
+    #[repr(transparent)]
+    #[derive(Clone, PartialEq, Eq, Debug)]
+    pub struct ISetupInstance(IUnknown);
+
+    unsafe impl Interface for ISetupInstance {
+        type Vtable = ISetupInstance_Vtbl;
+        const IID: GUID = GUID::from_u128(0xB41463C3_8866_43B5_BC33_2B0676F7F42E);
+    }
+
+    impl ISetupInstance {
+        pub unsafe fn installation_path(&self) -> Result<String> {
+            let mut bstr = BSTR::default();
+            (Interface::vtable(self).GetInstallationPath)(Interface::as_raw(self), &mut bstr).ok()?;
+            Ok(bstr.to_string())
+        }
+
+        pub unsafe fn installation_version(&self) -> Result<String> {
+            let mut bstr = BSTR::default();
+            (Interface::vtable(self).GetInstallationVersion)(Interface::as_raw(self), &mut bstr).ok()?;
+            Ok(bstr.to_string())
+        }
+    }
+}
+
 #[derive(Parser, Debug)]
 #[command(version, author, about = "Run a command under your favourite Developer Shell Prompt", after_help = "Inspired by https://github.com/ilammy/msvc-dev-cmd")]
 struct Opt {
     /// Target architecture
-    #[arg(long, default_value = "x64")]
-    arch: String,
+    #[arg(long)]
+    arch: Option<String>,
 
     /// Windows SDK number to build for
     #[arg(long)]
     sdk: Option<String>,
 
+    /// When no --sdk is given, auto-select the newest installed Windows SDK.
+    #[arg(long, default_value_t = false)]
+    latest_sdk: bool,
+
     /// Enable Spectre mitigations
     #[arg(long, default_value_t = false)]
     spectre: bool,
@@ -42,8 +148,18 @@ struct Opt {
     #[arg(long)]
     vsversion: Option<String>,
 
+    /// Skip the already-configured-environment check and always re-run vcvarsall.
+    #[arg(long, default_value_t = false)]
+    force: bool,
+
+    /// Resolve the absolute path to an MSVC tool (e.g. cl.exe, link.exe, msbuild, devenv) and
+    /// print it instead of running a program.
+    #[arg(long, conflicts_with = "program")]
+    which: Option<String>,
+
     /// Name or path to the program I'll background to.
-    program: PathBuf,
+    #[arg(required_unless_present = "which")]
+    program: Option<PathBuf>,
 
     /// Arguments to the program.
     args: Vec<PathBuf>,
@@ -134,6 +250,130 @@ impl Constants<'_> {
         Ok(res)
     }
 
+    #[cfg(windows)]
+    fn find_with_setup_config(&self, vsversion: &Option<String>) -> Result<PathBuf> {
+        use setup_configuration::ISetupConfiguration;
+        use windows::core::GUID;
+        use windows::Win32::System::Com::{CoCreateInstance, CoInitializeEx, CLSCTX_ALL, COINIT_MULTITHREADED};
+
+        let wanted_major = self.vsversion_to_versionnumber(vsversion).map(|v| v.split('.').collect::<Vec<_>>()[0].to_string());
+
+        const CLSID_SETUP_CONFIGURATION: GUID = GUID::from_u128(0x177F0C4A_1CD3_4DE7_A32C_71DBBB9FA36D);
+
+        unsafe {
+            CoInitializeEx(None, COINIT_MULTITHREADED).ok()?;
+
+            let query: ISetupConfiguration = CoCreateInstance(&CLSID_SETUP_CONFIGURATION, None, CLSCTX_ALL)?;
+            let instances = query.enum_instances()?;
+
+            let mut best: Option<(String, PathBuf)> = None;
+            while let Some(instance) = instances.next_one()? {
+                let version = instance.installation_version()?;
+                if let Some(major) = &wanted_major {
+                    if !version.starts_with(major.as_str()) {
+                        continue;
+                    }
+                }
+
+                let better = best.as_ref().map_or(true, |(best_version, _)| version.as_str() > best_version.as_str());
+                if !better {
+                    continue;
+                }
+
+                best = Some((version, PathBuf::from(instance.installation_path()?)));
+            }
+
+            match best {
+                Some((version, install_path)) => {
+                    let res = canonicalize(install_path.join("VC/Auxiliary/Build/vcvarsall.bat"))?;
+                    log::debug!("Result of setup config query (version {}): {}", version, res.display());
+                    Ok(res)
+                },
+                None => bail!("Query to the Setup Configuration API found no matching Visual Studio instance"),
+            }
+        }
+    }
+
+    #[cfg(windows)]
+    fn find_in_registry(&self, vsversion: &Option<String>) -> Result<PathBuf> {
+        use winreg::enums::{HKEY_LOCAL_MACHINE, KEY_READ, KEY_WOW64_32KEY};
+        use winreg::RegKey;
+
+        let wanted_version = self.vsversion_to_versionnumber(vsversion);
+        let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
+
+        // VS 2017+ publishes its VC install directory directly under VC7. Only the
+        // WOW6432Node path needs KEY_WOW64_32KEY explicitly: passing it on the native
+        // path would force that one into the 32-bit view too, so a native 64-bit VS
+        // install would never be found.
+        for (subkey, flags) in [
+            ("SOFTWARE\\Microsoft\\VisualStudio\\SxS\\VC7", KEY_READ),
+            ("SOFTWARE\\WOW6432Node\\Microsoft\\VisualStudio\\SxS\\VC7", KEY_READ | KEY_WOW64_32KEY),
+        ] {
+            let vc7 = match hklm.open_subkey_with_flags(subkey, flags) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+
+            let mut versions = vc7.enum_values().filter_map(|r| r.ok()).filter_map(|(name, value)| {
+                let dir: String = value.try_into().ok()?;
+                Some((name, dir))
+            }).collect::<Vec<_>>();
+
+            versions.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+
+            for (version, dir) in versions {
+                if let Some(wanted) = wanted_version {
+                    if !version.eq(wanted) {
+                        continue;
+                    }
+                }
+
+                let f = canonicalize(Path::new(&dir).join("Auxiliary/Build/vcvarsall.bat"));
+                if let Ok(f) = f {
+                    return Ok(f);
+                }
+            }
+        }
+
+        // Older layout (VS 2015 and earlier) keeps the product directory under Setup/VS.
+        for (subkey, flags) in [
+            ("SOFTWARE\\Microsoft\\VisualStudio", KEY_READ),
+            ("SOFTWARE\\WOW6432Node\\Microsoft\\VisualStudio", KEY_READ | KEY_WOW64_32KEY),
+        ] {
+            let vs = match hklm.open_subkey_with_flags(subkey, flags) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+
+            let mut versions = vs.enum_keys().filter_map(|r| r.ok()).collect::<Vec<_>>();
+            versions.sort_by(|a, b| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+
+            for version in versions {
+                if let Some(wanted) = wanted_version {
+                    if !version.eq(wanted) {
+                        continue;
+                    }
+                }
+
+                let product_dir: String = match vs.open_subkey_with_flags(format!("{}\\Setup\\VS", version), KEY_READ) {
+                    Ok(setup) => match setup.get_value("ProductDir") {
+                        Ok(v) => v,
+                        Err(_) => continue,
+                    },
+                    Err(_) => continue,
+                };
+
+                let f = canonicalize(Path::new(&product_dir).join("VC/Auxiliary/Build/vcvarsall.bat"));
+                if let Ok(f) = f {
+                    return Ok(f);
+                }
+            }
+        }
+
+        bail!("Visual Studio not found in the registry")
+    }
+
     fn find_vcvarsall(&self, vsversion: &Option<String>) -> Result<PathBuf> {
         let vsversion_number = self.vsversion_to_versionnumber(vsversion);
         let version_pattern = match vsversion_number {
@@ -143,7 +383,23 @@ impl Constants<'_> {
             },
             None => "-latest".to_string()
         };
-    
+
+        // Try the native Setup Configuration COM API first; it doesn't depend on
+        // vswhere.exe being present or unlocalized.
+        #[cfg(windows)]
+        {
+            let path = self.find_with_setup_config(vsversion);
+            match path {
+                Ok(v) => {
+                    log::info!("Found with Setup Configuration API: {}", v.display());
+                    return Ok(v);
+                },
+                Err(v) => {
+                    log::info!("Not found with Setup Configuration API: {}", v)
+                }
+            }
+        }
+
         // If vswhere is available, ask it about the location of the latest Visual Studio.
         {
             let path = self.find_with_vswhere("VC/Auxiliary/Build/vcvarsall.bat", &version_pattern);
@@ -157,7 +413,23 @@ impl Constants<'_> {
                 }
             }
         }
-    
+
+        // If that does not work, try the Windows Registry, which is populated
+        // even on machines where vswhere.exe was stripped out of the install.
+        #[cfg(windows)]
+        {
+            let path = self.find_in_registry(vsversion);
+            match path {
+                Ok(v) => {
+                    log::info!("Found in registry: {}", v.display());
+                    return Ok(v);
+                },
+                Err(v) => {
+                    log::info!("Not found in registry: {}", v)
+                }
+            }
+        }
+
         // If that does not work, try the standard installation locations,
         // starting with the latest and moving to the oldest.
         let years = match vsversion {
@@ -193,6 +465,63 @@ impl Constants<'_> {
 
         bail!("Microsoft Visual Studio not found")
     }
+
+    #[cfg(windows)]
+    fn installed_sdks(&self) -> Result<Vec<String>> {
+        use winreg::enums::{HKEY_LOCAL_MACHINE, KEY_READ, KEY_WOW64_32KEY};
+        use winreg::RegKey;
+
+        let hklm = RegKey::predef(HKEY_LOCAL_MACHINE);
+
+        // Only the WOW6432Node path needs KEY_WOW64_32KEY explicitly: passing it on
+        // the native path would force that one into the 32-bit view too.
+        let mut kits_roots = Vec::new();
+        for (subkey, flags) in [
+            ("SOFTWARE\\Microsoft\\Windows Kits\\Installed Roots", KEY_READ),
+            ("SOFTWARE\\WOW6432Node\\Microsoft\\Windows Kits\\Installed Roots", KEY_READ | KEY_WOW64_32KEY),
+        ] {
+            if let Ok(key) = hklm.open_subkey_with_flags(subkey, flags) {
+                if let Ok(root) = key.get_value::<String, _>("KitsRoot10") {
+                    kits_roots.push(root);
+                }
+            }
+        }
+
+        let mut versions = kits_roots.iter().filter_map(|root| std::fs::read_dir(Path::new(root).join("Include")).ok()).flatten().filter_map(|entry| entry.ok()).filter_map(|entry| entry.file_name().to_str().map(String::from)).filter(|name| name.starts_with("10.0.")).collect::<Vec<_>>();
+
+        versions.sort();
+        versions.dedup();
+
+        Ok(versions)
+    }
+
+    /// Validate a requested `--sdk` against the installed set, expanding a bare
+    /// "10" to the newest installed `10.0.*` build.
+    #[cfg(windows)]
+    fn resolve_sdk(&self, sdk: &str) -> Result<String> {
+        // We only enumerate installed Windows 10 SDKs; forward anything else
+        // (8.1, 7.1A, ...) verbatim, same as before --sdk was validated.
+        if sdk != "10" && !sdk.starts_with("10.") {
+            return Ok(sdk.to_string());
+        }
+
+        let installed = self.installed_sdks()?;
+
+        if sdk == "10" {
+            return installed.into_iter().max().ok_or_else(|| anyhow::anyhow!("No Windows 10 SDK is installed"));
+        }
+
+        if installed.iter().any(|v| v.eq(sdk)) {
+            return Ok(sdk.to_string());
+        }
+
+        bail!("Windows SDK {} is not installed; available versions: {}", sdk, installed.join(", "))
+    }
+
+    #[cfg(windows)]
+    fn latest_sdk(&self) -> Result<String> {
+        self.installed_sdks()?.into_iter().max().ok_or_else(|| anyhow::anyhow!("No Windows SDK is installed"))
+    }
 }
 
 
@@ -243,7 +572,7 @@ fn setup_msvcdev_cmd(opt: &Opt) -> Result<()> {
     // Ignore case when matching as that's what humans expect.
     
     let arch: String = {
-        let arch_lowercase = opt.arch.to_lowercase();
+        let arch_lowercase = opt.arch.as_deref().unwrap_or("x64").to_lowercase();
 
         match arch_aliases.get(arch_lowercase.as_str()) {
             Some(v) => v.to_string(),
@@ -251,6 +580,53 @@ fn setup_msvcdev_cmd(opt: &Opt) -> Result<()> {
         }
     };
 
+    // Skip vcvarsall if we're already inside a matching dev prompt (mirrors the
+    // cc crate's heuristic). An explicitly requested --arch, --sdk or --toolset
+    // must still match the active environment, or we fall through and actually
+    // reconfigure; --vsversion, --uwp, --spectre and --latest-sdk are not compared.
+    if !opt.force && env::var_os("VCINSTALLDIR").is_some() {
+        let arch_matches = opt.arch.is_none() || match env::var("VSCMD_ARG_TGT_ARCH") {
+            Ok(v) => v.eq_ignore_ascii_case(&arch),
+            Err(_) => false
+        };
+        let sdk_matches = match &opt.sdk {
+            None => true,
+            Some(requested) => match env::var("WindowsSDKVersion") {
+                Ok(v) => v.trim_end_matches('\\').eq_ignore_ascii_case(requested),
+                Err(_) => false
+            }
+        };
+        let toolset_matches = match &opt.toolset {
+            None => true,
+            Some(requested) => match env::var("VSCMD_ARG_VCVARS_VER") {
+                Ok(v) => v.eq_ignore_ascii_case(requested),
+                Err(_) => false
+            }
+        };
+
+        if arch_matches && sdk_matches && toolset_matches {
+            log::info!("Developer Command Prompt already configured, skipping vcvarsall");
+            return Ok(());
+        }
+    }
+
+    // Validate/select the SDK up front rather than letting a bad value surface
+    // later as an obscure "[ERROR" line scraped from vcvarsall.bat's output.
+    let sdk: Option<String> = {
+        #[cfg(windows)]
+        {
+            match &opt.sdk {
+                Some(v) => Some(constants.resolve_sdk(v)?),
+                None if opt.latest_sdk => Some(constants.latest_sdk()?),
+                None => None
+            }
+        }
+        #[cfg(not(windows))]
+        {
+            opt.sdk.clone()
+        }
+    };
+
     // Due to the way Microsoft Visual C++ is configured, we have to resort to the following hack:
     // Call the configuration batch file and then output *all* the environment variables.
 
@@ -259,7 +635,7 @@ fn setup_msvcdev_cmd(opt: &Opt) -> Result<()> {
         if opt.uwp {
             args.push("uwp".to_string())
         }
-        match &opt.sdk {
+        match &sdk {
             Some(v) => args.push(v.to_string()),
             None => {}
         }
@@ -396,6 +772,46 @@ fn setup_msvcdev_cmd(opt: &Opt) -> Result<()> {
     Ok(())
 }
 
+/// Tools that aren't on the compiler PATH: derive their location from the
+/// Visual Studio installation root instead of walking PATH for them.
+const IDE_TOOLS: [(&str, &str); 2] = [
+    ("msbuild", "MSBuild/Current/Bin/MSBuild.exe"),
+    ("devenv", "Common7/IDE/devenv.exe"),
+];
+
+/// Resolve the absolute path to an MSVC tool, mirroring the cc crate's `find_tool`.
+fn locate_tool(opt: &Opt, tool: &str) -> Result<PathBuf> {
+    let stem = Path::new(tool).file_stem().and_then(|s| s.to_str()).unwrap_or(tool).to_lowercase();
+
+    if let Some((_, relative)) = IDE_TOOLS.iter().find(|(name, _)| name.eq(&stem)) {
+        let vcvarsall = Constants::new()?.find_vcvarsall(&opt.vsversion)?;
+        // vcvarsall.bat lives at "<install root>/VC/Auxiliary/Build/vcvarsall.bat".
+        let install_root = vcvarsall.ancestors().nth(4).with_context(|| format!("Could not determine the Visual Studio installation root from {}", vcvarsall.display()))?;
+
+        let candidate = install_root.join(relative);
+        if candidate.exists() {
+            return Ok(candidate);
+        }
+        bail!("Could not find {} under {}", tool, install_root.display());
+    }
+
+    let path = env::var_os("PATH").unwrap_or_default();
+    for dir in env::split_paths(&path) {
+        let candidate = dir.join(tool);
+        if candidate.exists() {
+            return Ok(candidate);
+        }
+        if Path::new(tool).extension().is_none() {
+            let with_exe = dir.join(format!("{}.exe", tool));
+            if with_exe.exists() {
+                return Ok(with_exe);
+            }
+        }
+    }
+
+    bail!("Could not find tool '{}' on PATH", tool)
+}
+
 fn main() -> Result<()> {
     env_logger::init();
 
@@ -403,9 +819,17 @@ fn main() -> Result<()> {
 
     setup_msvcdev_cmd(&opt)?;
 
-    log::info!("Launching: '{}' with args: {:?}", opt.program.to_string_lossy(), opt.args.iter().map(|x| x.to_string_lossy()).collect::<Vec<_>>());
+    if let Some(tool) = &opt.which {
+        let path = locate_tool(&opt, tool)?;
+        println!("{}", path.display());
+        return Ok(());
+    }
+
+    let program = opt.program.clone().expect("clap guarantees program is set when --which is absent");
+
+    log::info!("Launching: '{}' with args: {:?}", program.to_string_lossy(), opt.args.iter().map(|x| x.to_string_lossy()).collect::<Vec<_>>());
 
-    let cmd = Command::new(opt.program).args(opt.args).spawn().context("Unable to spawn program")?;
+    let cmd = Command::new(program).args(opt.args).spawn().context("Unable to spawn program")?;
 
     let arc = Arc::new(Mutex::new(cmd));
 